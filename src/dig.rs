@@ -0,0 +1,143 @@
+use hickory_client::proto::dnssec::rdata::DNSSECRData;
+use hickory_client::proto::op::{Message, MessageType, OpCode};
+use hickory_client::proto::rr::rdata::opt::{EdnsCode, EdnsOption};
+use hickory_client::proto::rr::{RData, Record};
+use std::fmt;
+
+/// Renders a message the way `dig` and `nsupdate -d` print them, so debug logs can be
+/// compared line by line with server-side query logs.
+///
+/// Formatting is deferred to `Display`, so wrapping a message in a disabled `debug!`
+/// costs nothing.
+pub struct Dig<'a>(pub &'a Message);
+
+impl fmt::Display for Dig<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = self.0;
+        let header = msg.header();
+        let is_update = header.op_code() == OpCode::Update;
+
+        write!(
+            f,
+            ";; ->>HEADER<<- opcode: {}, status: {}, id: {}",
+            mnemonic(header.op_code()),
+            mnemonic(msg.response_code()),
+            header.id()
+        )?;
+
+        let flags = [
+            (header.message_type() == MessageType::Response, "qr"),
+            (header.authoritative(), "aa"),
+            (header.truncated(), "tc"),
+            (header.recursion_desired(), "rd"),
+            (header.recursion_available(), "ra"),
+            (header.authentic_data(), "ad"),
+            (header.checking_disabled(), "cd"),
+        ]
+        .into_iter()
+        .filter_map(|(set, name)| set.then_some(name))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+        let names = if is_update {
+            ["ZONE", "PREREQ", "UPDATE", "ADDITIONAL"]
+        } else {
+            ["QUERY", "ANSWER", "AUTHORITY", "ADDITIONAL"]
+        };
+        write!(
+            f,
+            "\n;; flags: {flags}; {}: {}, {}: {}, {}: {}, {}: {}",
+            names[0],
+            msg.queries().len(),
+            names[1],
+            msg.answers().len(),
+            names[2],
+            msg.name_servers().len(),
+            names[3],
+            msg.additionals().len() + msg.signature().len()
+        )?;
+
+        if let Some(edns) = msg.extensions() {
+            write!(f, "\n\n;; OPT PSEUDOSECTION:")?;
+            write!(
+                f,
+                "\n; EDNS: version: {}, flags:{}; udp: {}",
+                edns.version(),
+                if edns.flags().dnssec_ok { " do" } else { "" },
+                edns.max_payload()
+            )?;
+            for (code, option) in edns.options().as_ref() {
+                match option {
+                    EdnsOption::Unknown(_, data) if *code == EdnsCode::UL && data.len() >= 4 => {
+                        let lease = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+                        write!(f, "\n; UL: {lease}")?;
+                    }
+                    _ => write!(f, "\n; {code:?}: {option:?}")?,
+                }
+            }
+        }
+
+        let (question, answer, authority) = if is_update {
+            ("ZONE SECTION", "PREREQUISITE SECTION", "UPDATE SECTION")
+        } else {
+            ("QUESTION SECTION", "ANSWER SECTION", "AUTHORITY SECTION")
+        };
+
+        write!(f, "\n\n;; {question}:")?;
+        for query in msg.queries() {
+            write!(
+                f,
+                "\n;{}\t\t{}\t{}",
+                query.name(),
+                query.query_class(),
+                query.query_type()
+            )?;
+        }
+
+        write_section(f, answer, msg.answers())?;
+        write_section(f, authority, msg.name_servers())?;
+        write_section(f, "ADDITIONAL SECTION", msg.additionals())?;
+        write_section(f, "SIG0 PSEUDOSECTION", msg.signature())
+    }
+}
+
+fn write_section(f: &mut fmt::Formatter<'_>, title: &str, records: &[Record]) -> fmt::Result {
+    if records.is_empty() {
+        return Ok(());
+    }
+    write!(f, "\n\n;; {title}:")?;
+    for record in records {
+        write!(
+            f,
+            "\n{}\t\t{}\t{}\t{}",
+            record.name(),
+            record.ttl(),
+            record.dns_class(),
+            record.record_type(),
+        )?;
+        match record.data() {
+            // Deletions carry no RDATA; nsupdate prints nothing rather than a placeholder.
+            RData::Update0(_) => {}
+            // The signature is left out so logs never carry a replayable signed message.
+            RData::DNSSEC(DNSSECRData::SIG(sig)) => write!(
+                f,
+                "\t{} {} {} {} {} {} {} {} [redacted]",
+                sig.type_covered(),
+                sig.algorithm(),
+                sig.num_labels(),
+                sig.original_ttl(),
+                sig.sig_expiration().get(),
+                sig.sig_inception().get(),
+                sig.key_tag(),
+                sig.signer_name(),
+            )?,
+            data => write!(f, "\t{data}")?,
+        }
+    }
+    Ok(())
+}
+
+/// dig prints opcodes and rcodes as upper-case mnemonics (`UPDATE`, `NXDOMAIN`).
+fn mnemonic(value: impl fmt::Debug) -> String {
+    format!("{value:?}").to_uppercase()
+}
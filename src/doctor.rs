@@ -0,0 +1,397 @@
+use color_eyre::eyre::{Result, eyre};
+use hickory_client::proto::rr::{Name, RecordType};
+use std::net::IpAddr;
+use std::time::Duration;
+use tokio::net::TcpStream;
+
+use crate::nat::{self, NatVerdict};
+use crate::network;
+use crate::origin::{self, Resolver};
+use crate::server::ServerAddr;
+use crate::signer::ZoneSigners;
+use crate::{DnsUpdater, UpdateArgs, connect, determine_ips, plan_zones};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(clap::Args, Debug)]
+pub struct DoctorArgs {
+    #[command(flatten)]
+    update: UpdateArgs,
+
+    /// STUN server used to learn the address other hosts see, for NAT detection.
+    #[arg(
+        long,
+        env = "WHODIS_STUN_SERVER",
+        default_value = "stun.l.google.com:19302"
+    )]
+    stun_server: String,
+
+    /// Recursive resolver for the reverse DNS and origin lookups of public addresses.
+    /// Defaults to the first nameserver in /etc/resolv.conf.
+    #[arg(long, env = "WHODIS_RESOLVER")]
+    resolver: Option<ServerAddr>,
+}
+
+/// Collects check outcomes and prints them as they come in.
+#[derive(Default)]
+struct Report {
+    failures: usize,
+}
+
+impl Report {
+    fn pass(&mut self, check: &str, detail: impl std::fmt::Display) {
+        println!("[PASS] {check}: {detail}");
+    }
+
+    fn warn(&mut self, check: &str, detail: impl std::fmt::Display, hint: &str) {
+        println!("[WARN] {check}: {detail}");
+        println!("       hint: {hint}");
+    }
+
+    fn fail(&mut self, check: &str, detail: impl std::fmt::Display, hint: &str) {
+        self.failures += 1;
+        println!("[FAIL] {check}: {detail}");
+        println!("       hint: {hint}");
+    }
+
+    fn skip(&mut self, check: &str, reason: &str) {
+        println!("[SKIP] {check}: {reason}");
+    }
+}
+
+/// Runs every check, continuing past failures so the report is complete.
+pub async fn run(args: DoctorArgs) -> Result<()> {
+    let mut report = Report::default();
+    let stun_server = args.stun_server;
+    let resolver = args.resolver;
+    let args = args.update;
+
+    let plan = match plan_zones(&args) {
+        Ok(plan) => {
+            for zone in &plan {
+                for host in &zone.hosts {
+                    report.pass(
+                        "hostname",
+                        format!("{host} is updated in zone {zone}", zone = zone.zone),
+                    );
+                }
+            }
+            plan
+        }
+        Err(e) => {
+            report.fail(
+                "zones",
+                format!("{e:#}"),
+                "Every --hostname must lie inside one of the --zone values, and every zone needs at least one hostname.",
+            );
+            Vec::new()
+        }
+    };
+
+    let mut reachable = Vec::new();
+    for zone in &plan {
+        let check = format!("key {}", zone.zone);
+        let mut signers = ZoneSigners::default();
+        if let Err(e) = signers.add(zone.zone.clone(), zone.key.expose()) {
+            report.fail(
+                &check,
+                format!("{e:#}"),
+                "Use a PEM-encoded RSA private key, e.g. `openssl genrsa -out dns_update.key 2048`.",
+            );
+            report.skip(&format!("zone {}", zone.zone), "needs a valid key");
+            continue;
+        }
+        report.pass(&check, "parses as RSASHA256 and derives a public key");
+
+        let is_reachable = match reachable.iter().find(|(server, _)| *server == zone.server) {
+            Some((_, ok)) => *ok,
+            None => {
+                let ok = check_reachable(&mut report, &zone.server).await;
+                reachable.push((zone.server.clone(), ok));
+                ok
+            }
+        };
+        if !is_reachable {
+            report.skip(&format!("zone {}", zone.zone), "needs a reachable server");
+            continue;
+        }
+
+        check_zone(&mut report, &zone.server, zone.zone.clone(), signers).await;
+    }
+
+    let ips = check_detection(&mut report, &args);
+    let observed = check_nat(&mut report, &ips, &stun_server).await;
+    check_origin(&mut report, &ips, observed, resolver.as_ref()).await;
+
+    if report.failures > 0 {
+        return Err(eyre!("{} check(s) failed", report.failures));
+    }
+    println!("All checks passed.");
+    Ok(())
+}
+
+async fn check_reachable(report: &mut Report, server: &ServerAddr) -> bool {
+    let addrs = match server.resolve().await {
+        Ok(addrs) => addrs,
+        Err(e) => {
+            report.fail(
+                "server",
+                format!("{e:#}"),
+                "Check the server hostname resolves from this host, or pass its address instead.",
+            );
+            return false;
+        }
+    };
+
+    // A server is usable as long as one address connects, since updates fail over to
+    // the next address; the others are only worth a warning then.
+    let mut reachable = false;
+    let mut unreachable = Vec::new();
+    for addr in addrs {
+        let target = if server.to_string() == addr.to_string() {
+            server.to_string()
+        } else {
+            format!("{server} ({addr})")
+        };
+        match tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(addr)).await {
+            Ok(Ok(_)) => {
+                report.pass("server", format!("{target} accepts TCP connections"));
+                reachable = true;
+            }
+            Ok(Err(e)) => unreachable.push((
+                format!("{target}: {e}"),
+                "Check --server and that the server listens on TCP; updates are always sent over TCP.",
+            )),
+            Err(_) => unreachable.push((
+                format!("{target}: no answer within {CONNECT_TIMEOUT:?}"),
+                "Check for firewalls dropping TCP port 53 between this host and the server.",
+            )),
+        }
+    }
+    for (detail, hint) in unreachable {
+        if reachable {
+            report.warn("server", detail, hint);
+        } else {
+            report.fail("server", detail, hint);
+        }
+    }
+    reachable
+}
+
+async fn check_zone(report: &mut Report, server: &ServerAddr, zone: Name, signers: ZoneSigners) {
+    let authoritative = format!("authoritative {zone}");
+    let accepted = format!("key accepted {zone}");
+
+    let client = match connect(server, signers).await {
+        Ok(client) => client,
+        Err(e) => {
+            report.fail(
+                &authoritative,
+                format!("{e:#}"),
+                "The TCP connection succeeded but the DNS handshake did not; is this a DNS server?",
+            );
+            report.skip(&accepted, "no DNS connection");
+            return;
+        }
+    };
+    let mut updater = DnsUpdater::new(client, zone.clone());
+
+    match updater.query_soa().await {
+        Ok(resp) => {
+            let has_soa = resp
+                .answers()
+                .iter()
+                .any(|r| r.record_type() == RecordType::SOA && r.name().eq_ignore_root(&zone));
+            if resp.authoritative() && has_soa {
+                report.pass(&authoritative, format!("{server} holds the SOA for {zone}"));
+            } else {
+                report.fail(
+                    &authoritative,
+                    format!(
+                        "SOA query for {zone} returned {} (authoritative: {})",
+                        resp.response_code(),
+                        resp.authoritative()
+                    ),
+                    "Make sure --zone matches a `[[zones]]` entry on the server, and that --server is the primary, not a resolver.",
+                );
+            }
+        }
+        Err(e) => report.fail(
+            &authoritative,
+            format!("{e:#}"),
+            "The server did not answer a plain SOA query; check its logs.",
+        ),
+    }
+
+    match updater.preflight().await {
+        Ok(()) => report.pass(&accepted, "server accepted a signed no-op update"),
+        Err(e) => report.fail(
+            &accepted,
+            format!("{e:#}"),
+            "Check the server's ZoneUpdateAuth key matches this key, `allow_update = true`, and that both clocks are in sync.",
+        ),
+    }
+}
+
+fn check_detection(report: &mut Report, args: &UpdateArgs) -> Vec<IpAddr> {
+    let ips = match determine_ips(&args.mode, args.ip.clone()) {
+        Ok(ips) if !ips.is_empty() => ips,
+        Ok(_) => {
+            report.fail(
+                "detection",
+                "no addresses detected",
+                "Bring up a network interface, select a different --mode, or pass --ip explicitly.",
+            );
+            return Vec::new();
+        }
+        Err(e) => {
+            report.fail(
+                "detection",
+                format!("{e:#}"),
+                "Select a different --mode, or pass --ip explicitly.",
+            );
+            return Vec::new();
+        }
+    };
+
+    for &ip in &ips {
+        match unusable_reason(&ip) {
+            None => report.pass("detection", format!("detected {ip}")),
+            Some(reason) => report.warn(
+                "detection",
+                format!("detected {ip}, which is {reason}"),
+                "Other hosts cannot reach this address; pass --ip or fix the interface configuration.",
+            ),
+        }
+    }
+
+    if !args.expect_ip.is_empty() {
+        match network::unexpected_address(&ips, &args.expect_ip) {
+            None => report.pass("expectation", "every address is within --expect-ip"),
+            Some(ip) => report.fail(
+                "expectation",
+                format!("{ip} is outside every --expect-ip prefix for its family"),
+                "If a VPN or proxy is up, its address was detected; pass --ip, or widen --expect-ip if the address is legitimate.",
+            ),
+        }
+    }
+    ips
+}
+
+/// Compares the interface address with the gateway's NAT-PMP external address and the
+/// STUN-observed address. Only IPv4 is checked; IPv6 is rarely translated.
+/// Returns the STUN-observed address, if the probe got that far.
+async fn check_nat(report: &mut Report, ips: &[IpAddr], stun_server: &str) -> Option<IpAddr> {
+    let Some(interface) = ips.iter().find_map(|ip| match ip {
+        IpAddr::V4(v4) => Some(*v4),
+        IpAddr::V6(_) => None,
+    }) else {
+        report.skip("nat", "no IPv4 address to compare");
+        return None;
+    };
+
+    let observed = match nat::stun_observed_address(stun_server).await {
+        Ok(observed) => observed,
+        Err(e) => {
+            report.skip("nat", &format!("STUN probe failed: {e:#}"));
+            return None;
+        }
+    };
+    let gateway_external = nat::gateway_external_address().await;
+
+    let seen = format!(
+        "interface {interface}, gateway external {}, observed {observed}",
+        gateway_external.map_or_else(|| "unknown".to_owned(), |ip| ip.to_string())
+    );
+    match nat::classify(interface, gateway_external, observed) {
+        NatVerdict::None => report.pass("nat", format!("no NAT ({seen})")),
+        NatVerdict::Single => report.pass("nat", format!("single NAT ({seen})")),
+        NatVerdict::Unknown => report.pass("nat", format!("behind NAT; gateway did not answer NAT-PMP ({seen})")),
+        NatVerdict::Double { gateway_external } => report.warn(
+            "nat",
+            format!("double NAT: the gateway's own external address {gateway_external} is translated again ({seen})"),
+            "Hosts outside your network cannot reach the gateway's address; publish it only for internal zones.",
+        ),
+        NatVerdict::CarrierGrade { shared } => report.warn(
+            "nat",
+            format!("carrier-grade NAT: {shared} is RFC 6598 shared address space ({seen})"),
+            "Your ISP shares the public address; inbound connections to it will not reach you.",
+        ),
+    }
+    Some(observed.into())
+}
+
+/// Shows reverse DNS and the announcing AS of every public address in play, so a VPN
+/// egress or corporate proxy stands out from the ISP's address.
+async fn check_origin(
+    report: &mut Report,
+    ips: &[IpAddr],
+    observed: Option<IpAddr>,
+    resolver: Option<&ServerAddr>,
+) {
+    let mut public: Vec<IpAddr> = Vec::new();
+    for ip in ips.iter().copied().chain(observed) {
+        if origin::is_public(ip) && !public.contains(&ip) {
+            public.push(ip);
+        }
+    }
+    if public.is_empty() {
+        report.skip("origin", "no public address to look up");
+        return;
+    }
+
+    let addr = match resolver {
+        Some(server) => server.resolve().await.map(|addrs| addrs[0]),
+        None => Resolver::system_default(),
+    };
+    let connected = match addr {
+        Ok(addr) => Resolver::connect(addr).await,
+        Err(e) => Err(e),
+    };
+    let mut resolver = match connected {
+        Ok(resolver) => resolver,
+        Err(e) => {
+            report.skip("origin", &format!("{e:#}"));
+            return;
+        }
+    };
+
+    for ip in public {
+        let ptr = match resolver.ptr(ip).await {
+            Ok(Some(name)) => format!("PTR {name}"),
+            Ok(None) => "no PTR".to_owned(),
+            Err(e) => format!("PTR lookup failed ({e:#})"),
+        };
+        match resolver.origin(ip).await {
+            Ok(Some(origin)) => report.pass(
+                "origin",
+                format!(
+                    "{ip}: {ptr}, AS{} {} ({}, {})",
+                    origin.asn,
+                    origin.as_name.as_deref().unwrap_or("(unnamed)"),
+                    origin.prefix,
+                    origin.country
+                ),
+            ),
+            Ok(None) => report.pass("origin", format!("{ip}: {ptr}, not announced by any AS")),
+            Err(e) => report.skip(
+                "origin",
+                &format!("{ip}: {ptr}, origin lookup failed: {e:#}"),
+            ),
+        }
+    }
+}
+
+fn unusable_reason(ip: &IpAddr) -> Option<&'static str> {
+    if ip.is_unspecified() {
+        return Some("unspecified");
+    }
+    if ip.is_loopback() {
+        return Some("a loopback address");
+    }
+    match ip {
+        IpAddr::V4(v4) if v4.is_link_local() => Some("link-local"),
+        IpAddr::V6(v6) if v6.is_unicast_link_local() => Some("link-local"),
+        _ => None,
+    }
+}
@@ -1,22 +1,45 @@
-use clap::{Parser, ValueEnum};
+use clap::{Parser, Subcommand, ValueEnum};
 use color_eyre::eyre::{Context, Result, eyre};
-use futures::StreamExt;
-use hickory_client::client::Client;
-use hickory_client::proto::dnssec::rdata::KEY;
-use hickory_client::proto::dnssec::{SigSigner, SigningKey};
-use hickory_client::proto::op::{Message, OpCode, Query, ResponseCode, UpdateMessage};
+use futures::{StreamExt, TryStreamExt, future, stream};
+use hickory_client::client::{Client, ClientHandle};
+use hickory_client::proto::op::{Edns, Message, OpCode, Query, ResponseCode, UpdateMessage};
+use hickory_client::proto::rr::rdata::NAPTR;
+use hickory_client::proto::rr::rdata::opt::{EdnsCode, EdnsOption};
 use hickory_client::proto::rr::{DNSClass, Name, RData, Record, RecordType, rdata};
 use hickory_client::proto::runtime::TokioRuntimeProvider;
 use hickory_client::proto::tcp::TcpClientStream;
-use hickory_client::proto::xfer::DnsHandle;
+use hickory_client::proto::xfer::{DnsHandle, DnsResponse};
 use local_ip_address::{local_ip, local_ipv6};
-use std::net::{IpAddr, SocketAddr};
+use std::borrow::Cow;
+use std::iter;
+use std::net::IpAddr;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
 use tracing::{debug, info, instrument};
 
+use crate::dig::Dig;
+use crate::network::Prefix;
+use crate::policy::Policy;
+use crate::records::{TxtTemplate, TxtVars};
+use crate::secret::Secret;
+use crate::server::ServerAddr;
+use crate::signer::ZoneSigners;
+
+mod dig;
+mod doctor;
 mod key_validator;
+mod metrics;
+mod nat;
+mod network;
+mod origin;
+mod policy;
+mod records;
+mod secret;
+mod server;
+mod signer;
 
 const KEY_BYTES: &[u8] = include_bytes!("../dns_update.key");
 
@@ -29,28 +52,182 @@ enum IpMode {
 }
 
 #[derive(Parser, Debug)]
-#[command(author, version, about)]
-struct Args {
-    /// The zone to update in (e.g. `dyn.lan`).
-    #[arg(short, long)]
-    zone: String,
+#[command(author, version, about, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    update: Option<UpdateArgs>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Check key, server, zone and address detection, printing a pass/fail report.
+    Doctor(doctor::DoctorArgs),
+}
+
+/// Everything needed for a single update. `doctor` takes the same arguments, so an
+/// existing invocation can be checked by inserting `doctor` in front of its flags.
+#[derive(clap::Args, Debug)]
+struct UpdateArgs {
+    /// The zone to update in (e.g. `dyn.lan`). Can be specified multiple times; each
+    /// hostname is updated in the most specific zone that contains it.
+    #[arg(
+        short,
+        long,
+        env = "WHODIS_ZONE",
+        value_delimiter = ',',
+        required = true
+    )]
+    zone: Vec<String>,
 
-    /// FQDN of the hostname entry (e.g. `laptop.dyn.lan`).
-    #[arg(short = 'n', long)]
-    hostname: String,
+    /// FQDN of the hostname entry (e.g. `laptop.dyn.lan`). Can be specified multiple times.
+    #[arg(
+        short = 'n',
+        long,
+        env = "WHODIS_HOSTNAME",
+        value_delimiter = ',',
+        required = true
+    )]
+    hostname: Vec<String>,
 
-    /// The DNS server to send the update to. Example: `192.168.1.53:53`.
-    #[arg(short, long)]
-    server: SocketAddr,
+    /// The DNS server to send the update to, as an address or hostname with an optional
+    /// port (default 53). Example: `192.168.1.53`, `[2001:db8::53]:53` or `ns1.dyn.lan`.
+    #[arg(short, long, env = "WHODIS_SERVER")]
+    server: ServerAddr,
+
+    /// Send one zone's update to a different server, as `ZONE=SERVER`.
+    /// Can be specified multiple times.
+    #[arg(long, env = "WHODIS_ZONE_SERVER", value_delimiter = ',', value_parser = parse_assignment::<ServerAddr>)]
+    zone_server: Vec<(String, ServerAddr)>,
 
     /// Restrict update to a specific protocol. Defaults to 'both'.
-    #[arg(value_enum, short = 'm', long, default_value_t = IpMode::Both)]
+    #[arg(value_enum, short = 'm', long, env = "WHODIS_MODE", default_value_t = IpMode::Both)]
     mode: IpMode,
 
     /// Explicit IP address(es) to set. Can be specified multiple times.
     /// If provided, auto-detection is skipped.
-    #[arg(long)]
+    #[arg(long, env = "WHODIS_IP", value_delimiter = ',')]
     ip: Vec<IpAddr>,
+
+    /// Refuse to publish an address outside these addresses or CIDR prefixes, e.g.
+    /// `203.0.113.0/24`. Only addresses of a family with an expectation are checked.
+    /// Catches publishing a VPN tunnel address. Can be specified multiple times.
+    #[arg(
+        long,
+        env = "WHODIS_EXPECT_IP",
+        value_delimiter = ',',
+        value_name = "PREFIX"
+    )]
+    expect_ip: Vec<Prefix>,
+
+    /// NAPTR record to publish for the hostname, in zone-file order:
+    /// `ORDER PREF "FLAGS" "SERVICE" "REGEXP" REPLACEMENT`. Can be specified multiple times;
+    /// replaces any existing NAPTR records. `WHODIS_NAPTR` holds a single record, since
+    /// regexps may contain any separator.
+    #[arg(long, env = "WHODIS_NAPTR", value_parser = records::parse_naptr)]
+    naptr: Vec<NAPTR>,
+
+    /// TXT record to publish for the hostname. `{ipv4}`, `{ipv6}`, `{hostname}` and
+    /// `{timestamp}` are replaced at update time. Can be specified multiple times;
+    /// replaces any existing TXT records.
+    #[arg(long, env = "WHODIS_TXT", value_parser = records::parse_txt_template)]
+    txt: Vec<TxtTemplate>,
+
+    /// Free-form note explaining why this change is made (e.g. "pre-move to new ISP").
+    /// Logged alongside the change; see also --publish-comment.
+    #[arg(long, env = "WHODIS_COMMENT")]
+    comment: Option<String>,
+
+    /// Also publish the comment, with a timestamp, as a TXT record at `_whodis.<hostname>`.
+    #[arg(long, env = "WHODIS_PUBLISH_COMMENT")]
+    publish_comment: bool,
+
+    /// Path to a PEM-encoded signing key, used instead of the key embedded at build time.
+    #[arg(long, env = "WHODIS_KEY_FILE", conflicts_with_all = ["key", "key_name"])]
+    key_file: Option<PathBuf>,
+
+    /// PEM-encoded signing key, used instead of the key embedded at build time.
+    /// Prefer passing this through the environment to keep it out of process listings.
+    #[arg(
+        long,
+        env = "WHODIS_KEY",
+        hide_env_values = true,
+        allow_hyphen_values = true,
+        conflicts_with = "key_name"
+    )]
+    key: Option<Secret<String>>,
+
+    /// Directory of PEM key files for `--key-name` and `--zone-key-name`.
+    #[arg(long, env = "WHODIS_KEY_DIR")]
+    key_dir: Option<PathBuf>,
+
+    /// Sign with the key `<NAME>.key` from `--key-dir`, used instead of the key embedded
+    /// at build time.
+    #[arg(long, env = "WHODIS_KEY_NAME", requires = "key_dir")]
+    key_name: Option<String>,
+
+    /// Sign one zone's update with a different PEM key file, as `ZONE=PATH`.
+    /// Can be specified multiple times.
+    #[arg(long, env = "WHODIS_ZONE_KEY", value_delimiter = ',', value_parser = parse_assignment::<PathBuf>)]
+    zone_key: Vec<(String, PathBuf)>,
+
+    /// Sign one zone's update with the key `<NAME>.key` from `--key-dir`, as
+    /// `ZONE=NAME`. Can be specified multiple times.
+    #[arg(long, env = "WHODIS_ZONE_KEY_NAME", value_delimiter = ',', value_parser = parse_assignment::<String>, requires = "key_dir")]
+    zone_key_name: Vec<(String, String)>,
+
+    /// Request an EDNS Update Lease: the server removes the records again unless they
+    /// are refreshed within this many seconds. Re-run well before the lease expires.
+    #[arg(long, env = "WHODIS_LEASE", value_name = "SECONDS")]
+    lease: Option<u32>,
+
+    /// TTL of the published records, in seconds.
+    #[arg(
+        long,
+        env = "WHODIS_TTL",
+        value_name = "SECONDS",
+        default_value_t = 300
+    )]
+    ttl: u32,
+
+    /// Use a different TTL for one hostname, as `HOST=SECONDS`, e.g. a short one for a
+    /// roaming laptop next to a static NAS. Can be specified multiple times.
+    #[arg(long, env = "WHODIS_HOST_TTL", value_delimiter = ',', value_parser = parse_assignment::<u32>)]
+    host_ttl: Vec<(String, u32)>,
+
+    /// Publish every replaced RRset with the TTL it already has, so records whose TTL the
+    /// zone admin tuned keep it. `--ttl`/`--host-ttl` only apply to RRsets that do not
+    /// exist yet.
+    #[arg(long, env = "WHODIS_KEEP_TTL")]
+    keep_ttl: bool,
+
+    /// Before detecting addresses, wait up to this many seconds for a routable address
+    /// and a default route. Useful at boot or after resume, when the network may lag.
+    #[arg(long, env = "WHODIS_WAIT_FOR_NETWORK", value_name = "SECONDS")]
+    wait_for_network: Option<u64>,
+
+    /// Before publishing, send every zone a signed no-op update and only go ahead if all
+    /// are accepted. Guards against publishing an address picked up behind a captive
+    /// portal or on an isolated network, where this probe cannot succeed.
+    #[arg(long, env = "WHODIS_CHECK_CONNECTIVITY")]
+    check_connectivity: bool,
+
+    /// How many servers to connect to and update at the same time. Each server gets its
+    /// own connection; keep this low to avoid overwhelming small primaries.
+    #[arg(long, env = "WHODIS_PARALLEL", value_name = "N", default_value = "1")]
+    parallel: NonZeroUsize,
+
+    /// Refuse, before signing, any change outside the zones, names, record types and
+    /// TTL bounds allowed by this policy file.
+    #[arg(long, env = "WHODIS_POLICY")]
+    policy: Option<PathBuf>,
+
+    /// Write the run's outcome in Prometheus exposition format to this file,
+    /// for node_exporter's textfile collector. Written on success and failure.
+    #[arg(long, env = "WHODIS_METRICS_TEXTFILE")]
+    metrics_textfile: Option<PathBuf>,
 }
 
 #[tokio::main]
@@ -63,40 +240,540 @@ async fn main() -> Result<()> {
         )
         .init();
 
-    let args = Args::parse();
+    let cli = Cli::parse();
+    match cli.command {
+        Some(Command::Doctor(args)) => doctor::run(args).await,
+        None => {
+            let args = cli
+                .update
+                .ok_or_else(|| eyre!("Missing update arguments"))?;
+            run_update(args).await
+        }
+    }
+}
+
+/// Runs an update, writing the metrics textfile and logging any failure.
+async fn run_update(args: UpdateArgs) -> Result<()> {
+    let metrics_textfile = args.metrics_textfile.clone();
+    let (zone, hostname) = (args.zone.join(","), args.hostname.join(","));
+    let started = Instant::now();
 
-    if let Err(e) = run_update_workflow(args).await {
+    let result = run_update_workflow(args).await;
+
+    if let Some(path) = metrics_textfile {
+        let metrics = metrics::RunMetrics {
+            zone: &zone,
+            hostname: &hostname,
+            success: result.is_ok(),
+            records: *result.as_ref().unwrap_or(&0),
+            duration: started.elapsed(),
+        };
+        if let Err(e) = metrics.write_textfile(&path) {
+            tracing::warn!("Failed to write metrics textfile: {:?}", e);
+        }
+    }
+
+    if let Err(e) = result {
         tracing::error!("DNS Update failed: {:?}", e);
         return Err(e);
     }
     Ok(())
 }
 
+/// Runs a single update, returning the number of address records published.
 #[instrument(skip(args))]
-async fn run_update_workflow(args: Args) -> Result<()> {
-    let ips = determine_ips(&args.mode, args.ip)?;
+async fn run_update_workflow(args: UpdateArgs) -> Result<usize> {
+    let plan = plan_zones(&args)?;
+    let policy = args.policy.as_deref().map(Policy::load).transpose()?;
+    if let Some(secs) = args.wait_for_network {
+        network::wait_until_ready(&args.mode, Duration::from_secs(secs)).await?;
+    }
+    let ips = determine_ips(&args.mode, args.ip.clone())?;
 
     if ips.is_empty() {
         // This likely means we are in 'Both' mode but found NO IPs at all,
         // or the user requested v6-only on a v4-only machine.
         return Err(eyre!("No applicable IP addresses found to update."));
     }
+    if let Some(ip) = network::unexpected_address(&ips, &args.expect_ip) {
+        let expected: Vec<String> = args.expect_ip.iter().map(ToString::to_string).collect();
+        return Err(eyre!(
+            "Refusing to publish {ip}: it is outside --expect-ip {}",
+            expected.join(", ")
+        ));
+    }
 
-    info!(hostname = %args.hostname, ips = ?ips, mode = ?args.mode, "Resolved update targets");
-
-    let zone_name = Name::from_str(&args.zone).context("Invalid zone name format")?;
-    let host_name = Name::from_str(&args.hostname).context("Invalid hostname format")?;
+    info!(hostnames = ?args.hostname, ips = ?ips, mode = ?args.mode, "Resolved update targets");
+    if let Some(comment) = &args.comment {
+        info!(comment = %comment, "Change annotated");
+    }
 
-    debug!(server = ?args.server, "Establishing authenticated connection");
-    let mut updater = DnsUpdater::connect(args.server, zone_name.clone(), KEY_BYTES).await?;
+    let builder = RecordBuilder {
+        args: &args,
+        ips: &ips,
+        now: SystemTime::now(),
+    };
+    let parallel = args.parallel.get();
+    let mut groups = group_by_server(plan);
 
-    info!("Dispatching DNS update request");
-    updater.apply_update(host_name, ips).await?;
+    // A server is connected to right before its zones are updated, since the primary
+    // may drop connections that sit idle while other servers are handled. Only
+    // --check-connectivity, and --policy together with --keep-ttl, connect to all of
+    // them up front, to probe them or look up TTLs before anything is published.
+    if args.check_connectivity {
+        connect_all(&mut groups, parallel)
+            .await
+            .wrap_err("Connectivity check failed; not publishing anything")?;
+    } else if policy.is_some() && args.keep_ttl {
+        connect_all(&mut groups, parallel)
+            .await
+            .wrap_err("Connecting to look up existing TTLs; not publishing anything")?;
+    }
+    if let Some(policy) = &policy {
+        check_policy(policy, &builder, &mut groups).await?;
+    }
+    if args.check_connectivity {
+        probe_all(&groups, parallel).await?;
+        info!("Connectivity check passed");
+    }
 
+    let outcomes = update_all(groups, &builder, parallel).await;
+    let addresses = summarize(outcomes)?;
     info!("DNS Update completed successfully");
+    Ok(addresses)
+}
+
+/// Opens the connection to every server up front. Nothing has been sent when this
+/// fails.
+async fn connect_all(groups: &mut [ServerUpdate], parallel: usize) -> Result<()> {
+    let clients: Vec<Client> = stream::iter(&*groups)
+        .map(|group| connect_zones(&group.server, &group.zones))
+        .buffered(parallel)
+        .try_collect()
+        .await?;
+    for (group, client) in groups.iter_mut().zip(clients) {
+        group.client = Some(client);
+    }
+    Ok(())
+}
+
+/// Builds every zone's updates and checks them against `policy`, keeping them for
+/// dispatch. Every zone has to pass before anything is sent, preflight included, so one
+/// disallowed zone cannot leave the others updated. Only the unsigned --keep-ttl
+/// lookups go out before this returns.
+async fn check_policy(
+    policy: &Policy,
+    builder: &RecordBuilder<'_>,
+    groups: &mut [ServerUpdate],
+) -> Result<()> {
+    for group in groups {
+        for zone in &mut group.zones {
+            let updates = builder.build(group.client.as_ref(), zone).await?;
+            policy.check(&zone.zone, &updates).with_context(|| {
+                format!(
+                    "Policy check for zone {}; not publishing anything",
+                    zone.zone
+                )
+            })?;
+            zone.updates = Some(updates);
+        }
+    }
     Ok(())
 }
 
+/// Sends every zone a signed no-op update over its open connection. Nothing is
+/// published unless all of them are accepted.
+async fn probe_all(groups: &[ServerUpdate], parallel: usize) -> Result<()> {
+    let probes = groups.iter().flat_map(|group| {
+        let client = group.client.as_ref();
+        group.zones.iter().filter_map(move |zone| {
+            let mut updater = DnsUpdater::new(client?.clone(), zone.zone.clone());
+            Some(async move {
+                updater.preflight().await.with_context(|| {
+                    format!(
+                        "Connectivity check for zone {}; not publishing anything",
+                        updater.zone
+                    )
+                })
+            })
+        })
+    });
+    stream::iter(probes)
+        .buffered(parallel)
+        .try_collect::<Vec<()>>()
+        .await?;
+    Ok(())
+}
+
+/// Updates every zone, connecting to servers that are not connected yet, with up to
+/// `parallel` servers at once. Returns each zone's outcome in argument order.
+async fn update_all(
+    groups: Vec<ServerUpdate>,
+    builder: &RecordBuilder<'_>,
+    parallel: usize,
+) -> Vec<(Name, Result<usize>)> {
+    stream::iter(groups)
+        .map(|group| async move {
+            let client = match group.client {
+                Some(client) => Ok(client),
+                None => connect_zones(&group.server, &group.zones).await,
+            };
+            match client {
+                // The client multiplexes requests by message id, so every zone on a
+                // connection can be in flight at once instead of paying one round trip
+                // per zone.
+                Ok(client) => {
+                    future::join_all(group.zones.into_iter().map(|zone| {
+                        let name = zone.zone.clone();
+                        let update = update_zone(builder, client.clone(), zone);
+                        async move { (name, update.await) }
+                    }))
+                    .await
+                }
+                // Every zone on the server failed the same way; only the first keeps the
+                // original report.
+                Err(e) => {
+                    let reason = format!("{e:#}");
+                    let errors = iter::once(e).chain(iter::repeat_with(|| eyre!("{reason}")));
+                    group
+                        .zones
+                        .into_iter()
+                        .zip(errors)
+                        .map(|(zone, e)| (zone.zone, Err(e)))
+                        .collect()
+                }
+            }
+        })
+        .buffered(parallel)
+        .flat_map(stream::iter)
+        .collect()
+        .await
+}
+
+/// Sends one zone's UPDATE, returning the number of address records published.
+async fn update_zone(
+    builder: &RecordBuilder<'_>,
+    client: Client,
+    mut zone: ZoneUpdate,
+) -> Result<usize> {
+    let updates = match zone.updates.take() {
+        Some(updates) => updates,
+        None => builder.build(Some(&client), &zone).await?,
+    };
+
+    let mut updater = DnsUpdater::new(client, zone.zone).with_lease(builder.args.lease);
+    info!(zone = %updater.zone, "Dispatching DNS update request");
+    updater.apply_update(updates).await?;
+    Ok(builder.ips.len() * zone.hosts.len())
+}
+
+/// Totals the published address records. A single zone's error is returned as is;
+/// with several zones, each failure is logged and the run fails as a whole.
+fn summarize(mut outcomes: Vec<(Name, Result<usize>)>) -> Result<usize> {
+    if outcomes.len() == 1 {
+        let (_, result) = outcomes.remove(0);
+        return result;
+    }
+
+    let mut addresses = 0;
+    let mut failed = 0;
+    for (zone, result) in &outcomes {
+        match result {
+            Ok(records) => {
+                info!(zone = %zone, records, "Zone updated");
+                addresses += records;
+            }
+            Err(e) => {
+                tracing::error!(zone = %zone, "Zone update failed: {e:#}");
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(eyre!("{failed} of {} zone updates failed", outcomes.len()));
+    }
+    Ok(addresses)
+}
+
+/// Turns the detected addresses and the record flags into each zone's RRsets.
+struct RecordBuilder<'a> {
+    args: &'a UpdateArgs,
+    ips: &'a [IpAddr],
+    now: SystemTime,
+}
+
+impl RecordBuilder<'_> {
+    /// The records published at `host` itself.
+    fn records_for(&self, host: &Name) -> Result<Vec<RData>> {
+        let vars = TxtVars {
+            hostname: host,
+            ips: self.ips,
+            now: self.now,
+        };
+        let txt = self
+            .args
+            .txt
+            .iter()
+            .map(|template| {
+                template
+                    .render(&vars)
+                    .map(|value| RData::TXT(records::txt(&value)))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| eyre!(e))?;
+
+        Ok(self
+            .ips
+            .iter()
+            .map(|ip| match *ip {
+                IpAddr::V4(addr) => RData::A(rdata::A(addr)),
+                IpAddr::V6(addr) => RData::AAAA(rdata::AAAA(addr)),
+            })
+            .chain(self.args.naptr.iter().cloned().map(RData::NAPTR))
+            .chain(txt)
+            .collect())
+    }
+
+    fn ttl_for(&self, host: &Name) -> Result<u32> {
+        Ok(lookup_override(&self.args.host_ttl, host)?
+            .copied()
+            .unwrap_or(self.args.ttl))
+    }
+
+    /// Builds the RRsets a zone's UPDATE replaces. `client` is only needed for the
+    /// --keep-ttl lookups.
+    async fn build(
+        &self,
+        client: Option<&Client>,
+        zone: &ZoneUpdate,
+    ) -> Result<Vec<(Name, u32, Vec<RData>)>> {
+        let mut lookup = client
+            .filter(|_| self.args.keep_ttl)
+            .map(|client| DnsUpdater::new(client.clone(), zone.zone.clone()));
+
+        let mut rrsets = Vec::new();
+        for host in &zone.hosts {
+            rrsets.push((host, host.clone(), self.records_for(host)?));
+
+            if let (Some(comment), true) = (&self.args.comment, self.args.publish_comment) {
+                let value = format!("{} {comment}", records::rfc3339(self.now));
+                let name = host
+                    .prepend_label("_whodis")
+                    .with_context(|| format!("Building comment record name for {host}"))?;
+                rrsets.push((host, name, vec![RData::TXT(records::txt(&value))]));
+            }
+        }
+
+        // Every replaced RRset gets its own TTL, so with --keep-ttl a tuned TXT or NAPTR
+        // TTL survives next to the addresses' one.
+        let mut updates = Vec::new();
+        for (host, name, records) in rrsets {
+            for (rtype, records) in group_by_type(records) {
+                let mut ttl = self.ttl_for(host)?;
+                if let Some(updater) = &mut lookup
+                    && let Some(existing) = updater.existing_ttl(&name, rtype).await?
+                {
+                    debug!(name = %name, %rtype, ttl = existing, "Keeping existing TTL");
+                    ttl = existing;
+                }
+                updates.push((name.clone(), ttl, records));
+            }
+        }
+        Ok(updates)
+    }
+}
+
+/// One UPDATE message: a zone, where to send it, how to sign it, and which hosts it covers.
+struct ZoneUpdate {
+    zone: Name,
+    server: ServerAddr,
+    key: Secret<Cow<'static, [u8]>>,
+    hosts: Vec<Name>,
+    /// The RRsets to replace, when they had to be built before anything was sent.
+    updates: Option<Vec<(Name, u32, Vec<RData>)>>,
+}
+
+/// Assigns every hostname to the most specific zone containing it, and resolves each
+/// zone's server and key from the per-zone overrides or the defaults.
+fn plan_zones(args: &UpdateArgs) -> Result<Vec<ZoneUpdate>> {
+    // Checked here rather than with clap's `requires`, which also fires for
+    // WHODIS_PUBLISH_COMMENT=false.
+    if args.publish_comment && args.comment.is_none() {
+        return Err(eyre!("--publish-comment needs a --comment to publish"));
+    }
+
+    let named_key = |name: &str| named_key(args.key_dir.as_deref(), name);
+    let default_key = match &args.key_name {
+        Some(name) => key_material(Some(&named_key(name)?), None)?,
+        None => key_material(args.key_file.as_deref(), args.key.as_ref())?,
+    };
+
+    let mut plan = Vec::new();
+    for zone in &args.zone {
+        let zone = Name::from_str(zone).with_context(|| format!("Invalid zone name {zone:?}"))?;
+        let server = lookup_override(&args.zone_server, &zone)?
+            .unwrap_or(&args.server)
+            .clone();
+        let key = match (
+            lookup_override(&args.zone_key, &zone)?,
+            lookup_override(&args.zone_key_name, &zone)?,
+        ) {
+            (Some(_), Some(_)) => {
+                return Err(eyre!("Zone {zone} has both --zone-key and --zone-key-name"));
+            }
+            (Some(path), None) => key_material(Some(path), None)?,
+            (None, Some(name)) => key_material(Some(&named_key(name)?), None)?,
+            (None, None) => default_key.clone(),
+        };
+        plan.push(ZoneUpdate {
+            zone,
+            server,
+            key,
+            hosts: Vec::new(),
+            updates: None,
+        });
+    }
+
+    for host in &args.hostname {
+        let host = Name::from_str(host).with_context(|| format!("Invalid hostname {host:?}"))?;
+        let zone = plan
+            .iter_mut()
+            .filter(|z| z.zone.zone_of(&host))
+            .max_by_key(|z| z.zone.num_labels())
+            .ok_or_else(|| eyre!("Hostname {host} is not inside any of the given zones"))?;
+        zone.hosts.push(host);
+    }
+
+    if let Some(unused) = plan.iter().find(|z| z.hosts.is_empty()) {
+        return Err(eyre!("Zone {} has no hostnames to update", unused.zone));
+    }
+
+    let known = |name: &str| {
+        Name::from_str(name).is_ok_and(|name| plan.iter().any(|z| z.zone.eq_ignore_root(&name)))
+    };
+    let overridden = args.zone_server.iter().map(|(name, _)| name);
+    let overridden = overridden
+        .chain(args.zone_key.iter().map(|(name, _)| name))
+        .chain(args.zone_key_name.iter().map(|(name, _)| name));
+    if let Some(name) = overridden.into_iter().find(|name| !known(name)) {
+        return Err(eyre!(
+            "Per-zone override given for {name}, which is not a --zone"
+        ));
+    }
+
+    let is_host = |name: &str| {
+        Name::from_str(name).is_ok_and(|name| {
+            plan.iter()
+                .flat_map(|z| &z.hosts)
+                .any(|host| host.eq_ignore_root(&name))
+        })
+    };
+    for (name, _) in &args.host_ttl {
+        if !is_host(name) {
+            return Err(eyre!(
+                "Per-host TTL given for {name}, which is not a --hostname"
+            ));
+        }
+    }
+    Ok(plan)
+}
+
+/// Finds the override for the zone or host `target`, if one was given.
+fn lookup_override<'a, T>(overrides: &'a [(String, T)], target: &Name) -> Result<Option<&'a T>> {
+    for (name, value) in overrides {
+        let name = Name::from_str(name).with_context(|| format!("Invalid name {name:?}"))?;
+        if name.eq_ignore_root(target) {
+            return Ok(Some(value));
+        }
+    }
+    Ok(None)
+}
+
+/// The zones sent to one server, and its connection once one is open.
+struct ServerUpdate {
+    server: ServerAddr,
+    zones: Vec<ZoneUpdate>,
+    client: Option<Client>,
+}
+
+/// Groups zone updates so each server gets a single connection, keeping argument order.
+fn group_by_server(plan: Vec<ZoneUpdate>) -> Vec<ServerUpdate> {
+    let mut groups: Vec<ServerUpdate> = Vec::new();
+    for zone in plan {
+        match groups.iter_mut().find(|group| group.server == zone.server) {
+            Some(group) => group.zones.push(zone),
+            None => groups.push(ServerUpdate {
+                server: zone.server.clone(),
+                zones: vec![zone],
+                client: None,
+            }),
+        }
+    }
+    groups
+}
+
+/// Splits records into one RRset per record type, keeping the order types first appear in.
+fn group_by_type(records: Vec<RData>) -> Vec<(RecordType, Vec<RData>)> {
+    let mut groups: Vec<(RecordType, Vec<RData>)> = Vec::new();
+    for record in records {
+        let rtype = record.record_type();
+        match groups.iter_mut().find(|(t, _)| *t == rtype) {
+            Some((_, records)) => records.push(record),
+            None => groups.push((rtype, vec![record])),
+        }
+    }
+    groups
+}
+
+/// Parses a `NAME=VALUE` per-zone or per-host override.
+fn parse_assignment<T>(value: &str) -> Result<(String, T), String>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    let (zone, rest) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=VALUE, got {value:?}"))?;
+    let parsed = rest
+        .parse()
+        .map_err(|e| format!("invalid value {rest:?}: {e}"))?;
+    Ok((zone.to_owned(), parsed))
+}
+
+/// Extracts the LEASE the server granted from an update response, if it sent one.
+fn granted_lease(resp: &Message) -> Option<u32> {
+    match resp.extensions().as_ref()?.options().get(EdnsCode::UL)? {
+        EdnsOption::Unknown(_, data) => Some(u32::from_be_bytes(data.get(..4)?.try_into().ok()?)),
+        _ => None,
+    }
+}
+
+/// Picks the signing key: an explicit file or inline PEM wins over the embedded key.
+fn key_material(
+    key_file: Option<&Path>,
+    key: Option<&Secret<String>>,
+) -> Result<Secret<Cow<'static, [u8]>>> {
+    if let Some(path) = key_file {
+        let bytes =
+            std::fs::read(path).with_context(|| format!("Reading key file {}", path.display()))?;
+        return Ok(Secret::new(Cow::Owned(bytes)));
+    }
+    if let Some(pem) = key {
+        return Ok(Secret::new(Cow::Owned(pem.expose().as_bytes().to_vec())));
+    }
+    Ok(Secret::new(Cow::Borrowed(KEY_BYTES)))
+}
+
+/// Resolves a logical key name to `<dir>/<name>.key`. Names are plain file stems, so
+/// they cannot point outside the key directory.
+fn named_key(dir: Option<&Path>, name: &str) -> Result<PathBuf> {
+    let dir = dir.ok_or_else(|| eyre!("Key name {name:?} given without --key-dir"))?;
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(eyre!("Invalid key name {name:?}"));
+    }
+    Ok(dir.join(format!("{name}.key")))
+}
+
 /// Determines which IPs to register based on the selected Mode.
 fn determine_ips(mode: &IpMode, explicit: Vec<IpAddr>) -> Result<Vec<IpAddr>> {
     // Helper: Returns true if the IP matches the requested mode logic
@@ -148,52 +825,143 @@ fn determine_ips(mode: &IpMode, explicit: Vec<IpAddr>) -> Result<Vec<IpAddr>> {
     Ok(detected)
 }
 
-struct DnsUpdater {
-    client: Client,
-    zone: Name,
+/// Connects to `server` with the keys of every zone it is updated for.
+async fn connect_zones(server: &ServerAddr, zones: &[ZoneUpdate]) -> Result<Client> {
+    let mut signers = ZoneSigners::default();
+    for zone in zones {
+        signers.add(zone.zone.clone(), zone.key.expose())?;
+    }
+    debug!(server = %server, "Establishing authenticated connection");
+    connect(server, signers).await
 }
 
-impl DnsUpdater {
-    async fn connect(server: SocketAddr, zone: Name, key_material: &[u8]) -> Result<Self> {
-        let signing_key = key_validator::load_and_validate(key_material)?;
-        let public_key = signing_key.to_public_key().context("Deriving public key")?;
-
-        let signer = SigSigner::sig0(
-            KEY::new_sig0key(&public_key),
-            Box::new(signing_key),
-            zone.clone(),
-        );
+/// Opens an authenticated connection, signing updates for every zone in `signers`.
+///
+/// The server is resolved afresh on every call and each of its addresses is tried in
+/// turn, so a primary that moved or lost one of several addresses is still reached.
+async fn connect(server: &ServerAddr, signers: ZoneSigners) -> Result<Client> {
+    let signers = Arc::new(signers);
+    let mut last_error = None;
 
+    for addr in server.resolve().await? {
         let (stream, sender) = TcpClientStream::new(
-            server,
+            addr,
             None,
             Some(Duration::from_secs(5)),
             TokioRuntimeProvider::new(),
         );
-        let (client, bg) = Client::new(stream, sender, Some(Arc::new(signer)))
-            .await
-            .context("DNS Handshake")?;
+        match Client::new(stream, sender, Some(signers.clone())).await {
+            Ok((client, bg)) => {
+                tokio::spawn(bg);
+                return Ok(client);
+            }
+            Err(e) => {
+                debug!(addr = %addr, error = %e, "Connection attempt failed");
+                last_error = Some(e);
+            }
+        }
+    }
+
+    Err(last_error.map_or_else(|| eyre!("No addresses to connect to"), Into::into))
+        .with_context(|| format!("DNS Handshake with {server}"))
+}
+
+struct DnsUpdater {
+    client: Client,
+    zone: Name,
+    /// Requested EDNS Update Lease in seconds, attached to address updates.
+    lease: Option<u32>,
+}
+
+impl DnsUpdater {
+    fn new(client: Client, zone: Name) -> Self {
+        Self {
+            client,
+            zone,
+            lease: None,
+        }
+    }
+
+    fn with_lease(mut self, lease: Option<u32>) -> Self {
+        self.lease = lease;
+        self
+    }
+
+    /// Replaces the records of every host in one UPDATE message, publishing each RRset
+    /// with the TTL given alongside it.
+    #[instrument(skip(self, updates), fields(zone = %self.zone))]
+    async fn apply_update(&mut self, updates: Vec<(Name, u32, Vec<RData>)>) -> Result<()> {
+        let msg = self.construct_packet(updates);
+        let resp = self.send_update(msg).await?;
 
-        tokio::spawn(bg);
-        Ok(Self { client, zone })
+        if let Some(requested) = self.lease {
+            match granted_lease(&resp) {
+                Some(granted) if granted != requested => {
+                    info!(requested, granted, "Server shortened the update lease");
+                }
+                Some(_) => debug!(lease = requested, "Server accepted the update lease"),
+                None => tracing::warn!("Server ignored the update lease; records will not expire"),
+            }
+        }
+        Ok(())
     }
 
-    #[instrument(skip(self), fields(zone = %self.zone))]
-    async fn apply_update(&mut self, host: Name, ips: Vec<IpAddr>) -> Result<()> {
-        let msg = self.construct_packet(host, ips);
+    /// Sends a signed UPDATE whose only content is the prerequisite that the zone apex
+    /// exists. The server authenticates it but has nothing to change, which makes it a
+    /// side-effect free way to learn whether our key is accepted.
+    async fn preflight(&mut self) -> Result<()> {
+        let mut msg = self.new_update();
+        let mut apex_in_use = Record::update0(self.zone.clone(), 0, RecordType::ANY);
+        apex_in_use.set_dns_class(DNSClass::ANY);
+        msg.add_pre_requisite(apex_in_use.into_record_of_rdata());
+        self.send_update(msg).await.map(drop)
+    }
 
+    /// Looks up the TTL the `rtype` RRset at `name` is currently published with. Returns
+    /// `None` if there is no such RRset.
+    async fn existing_ttl(&mut self, name: &Name, rtype: RecordType) -> Result<Option<u32>> {
+        let resp = self
+            .client
+            .query(name.clone(), DNSClass::IN, rtype)
+            .await
+            .with_context(|| format!("Network error looking up the {rtype} TTL of {name}"))?;
+        debug!("Received response\n{}", Dig(&resp));
+        Ok(resp
+            .answers()
+            .iter()
+            .find(|r| r.record_type() == rtype && r.name().eq_ignore_root(name))
+            .map(Record::ttl))
+    }
+
+    /// Queries the zone's SOA record, returning the raw response.
+    async fn query_soa(&mut self) -> Result<DnsResponse> {
+        let resp = self
+            .client
+            .query(self.zone.clone(), DNSClass::IN, RecordType::SOA)
+            .await
+            .context("Network error during SOA query")?;
+        debug!("Received response\n{}", Dig(&resp));
+        Ok(resp)
+    }
+
+    async fn send_update(&mut self, msg: Message) -> Result<DnsResponse> {
+        // The multiplexer assigns the final message id, so it can differ from the one logged.
+        debug!("Sending update\n{}", Dig(&msg));
         let mut response_stream = self.client.send(msg);
         match response_stream.next().await {
-            Some(Ok(resp)) => match resp.response_code() {
-                ResponseCode::NoError => Ok(()),
-                code => Err(eyre!("Server refused update: {}", code)),
-            },
+            Some(Ok(resp)) => {
+                debug!("Received response\n{}", Dig(&resp));
+                match resp.response_code() {
+                    ResponseCode::NoError => Ok(resp),
+                    code => Err(eyre!("Server refused update: {}", code)),
+                }
+            }
             Some(Err(e)) => Err(e).context("Network error during update"),
             None => Err(eyre!("Connection closed unexpectedly")),
         }
     }
 
-    fn construct_packet(&self, host: Name, ips: Vec<IpAddr>) -> Message {
+    fn new_update(&self) -> Message {
         let mut msg = Message::new();
         msg.set_op_code(OpCode::Update);
         msg.set_id(rand::random());
@@ -202,24 +970,255 @@ impl DnsUpdater {
         zone_section.set_name(self.zone.clone());
         zone_section.set_query_type(RecordType::SOA);
         msg.add_zone(zone_section);
+        msg
+    }
 
-        for ip in ips {
-            let (rdata, rtype) = match ip {
-                IpAddr::V4(addr) => (RData::A(rdata::A(addr)), RecordType::A),
-                IpAddr::V6(addr) => (RData::AAAA(rdata::AAAA(addr)), RecordType::AAAA),
-            };
+    fn construct_packet(&self, updates: Vec<(Name, u32, Vec<RData>)>) -> Message {
+        let mut msg = self.new_update();
 
-            // Class ANY + Specific Type = Delete that RRSet.
-            let mut delete_op = Record::update0(host.clone(), 0, rtype);
-            delete_op.set_dns_class(DNSClass::ANY);
-            msg.add_update(delete_op.into_record_of_rdata());
+        if let Some(lease) = self.lease {
+            // draft-ietf-dnssd-update-lease: a single 32-bit LEASE in seconds.
+            let mut edns = Edns::new();
+            edns.options_mut().insert(EdnsOption::Unknown(
+                EdnsCode::UL.into(),
+                lease.to_be_bytes().to_vec(),
+            ));
+            msg.set_edns(edns);
+        }
+
+        for (host, ttl, records) in updates {
+            // Class ANY + Specific Type = Delete that RRSet. Each type is cleared once, before
+            // any additions, so several records of the same type all survive.
+            let mut replaced = Vec::new();
+            for rtype in records.iter().map(RData::record_type) {
+                if !replaced.contains(&rtype) {
+                    replaced.push(rtype);
+                    let mut delete_op = Record::update0(host.clone(), 0, rtype);
+                    delete_op.set_dns_class(DNSClass::ANY);
+                    msg.add_update(delete_op.into_record_of_rdata());
+                }
+            }
 
-            let mut add_op = Record::from_rdata(host.clone(), 300, rdata.clone());
-            add_op.set_dns_class(DNSClass::IN);
-            add_op.set_data(rdata);
-            msg.add_update(add_op);
+            for rdata in records {
+                let mut add_op = Record::from_rdata(host.clone(), ttl, rdata);
+                add_op.set_dns_class(DNSClass::IN);
+                msg.add_update(add_op);
+            }
         }
 
         msg
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_client::proto::dnssec::rdata::DNSSECRData;
+    use hickory_client::proto::op::{MessageFinalizer, MessageType};
+    use std::io;
+    use std::sync::Mutex;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const KEY_PEM: &str = match std::str::from_utf8(KEY_BYTES) {
+        Ok(pem) => pem,
+        Err(_) => panic!("embedded key is not PEM"),
+    };
+
+    /// Asserts that `text` carries neither the PEM armour nor any line of the key body.
+    fn assert_no_key_material(text: &str) {
+        assert!(!text.contains("BEGIN"), "PEM armour leaked: {text}");
+        assert!(!text.contains("PRIVATE KEY"), "PEM armour leaked: {text}");
+        for line in KEY_PEM.lines().filter(|line| !line.starts_with("-----")) {
+            assert!(!text.contains(line), "key bytes leaked: {text}");
+        }
+    }
+
+    /// hickory's rendering of a SIG ends in the base64 signature.
+    fn signature_of(record: &Record) -> String {
+        let RData::DNSSEC(DNSSECRData::SIG(sig)) = record.data() else {
+            panic!("expected a SIG record, got {record}");
+        };
+        sig.to_string().rsplit(' ').next().unwrap().to_owned()
+    }
+
+    fn update_args(server: &str, extra: &[&str]) -> UpdateArgs {
+        let args = [
+            "whodis",
+            "--zone",
+            "example.com",
+            "--hostname",
+            "h.example.com",
+            "--server",
+            server,
+            "--ip",
+            "192.0.2.1",
+        ];
+        let cli = Cli::try_parse_from(args.iter().chain(extra)).unwrap();
+        cli.update.unwrap()
+    }
+
+    #[derive(Clone, Default)]
+    struct LogBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for LogBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Answers every message on one TCP connection by echoing it back as a response,
+    /// SIG(0) included, and collects the signatures it saw.
+    async fn echo_server() -> (ServerAddr, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = listener.local_addr().unwrap().to_string().parse().unwrap();
+        let signatures = Arc::new(Mutex::new(Vec::new()));
+        let seen = signatures.clone();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut len = [0; 2];
+            while stream.read_exact(&mut len).await.is_ok() {
+                let mut request = vec![0; u16::from_be_bytes(len).into()];
+                stream.read_exact(&mut request).await.unwrap();
+                let mut msg = Message::from_vec(&request).unwrap();
+                seen.lock()
+                    .unwrap()
+                    .extend(msg.signature().iter().map(signature_of));
+                msg.set_message_type(MessageType::Response);
+                let response = msg.to_vec().unwrap();
+                let len = u16::try_from(response.len()).unwrap().to_be_bytes();
+                stream.write_all(&len).await.unwrap();
+                stream.write_all(&response).await.unwrap();
+            }
+        });
+        (server, signatures)
+    }
+
+    // A current-thread runtime, so the connection's background task logs into the
+    // thread-local subscriber too.
+    #[tokio::test]
+    async fn logs_do_not_leak_key_or_signature() {
+        let logs = LogBuffer::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::TRACE)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let (server, signatures) = echo_server().await;
+        let server = server.to_string();
+        run_update(update_args(&server, &["--keep-ttl"]))
+            .await
+            .unwrap();
+
+        let lines: Vec<&str> = KEY_PEM.lines().collect();
+        let truncated = [&lines[..lines.len() / 2], &lines[lines.len() - 1..]]
+            .concat()
+            .join("\n");
+        let err = run_update(update_args(&server, &["--key", &truncated]))
+            .await
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("Loading key"), "{err:#}");
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(
+            logs.contains("SIG0 PSEUDOSECTION"),
+            "no signed message logged"
+        );
+        assert!(logs.contains("[redacted]"));
+        assert!(logs.contains("DNS Update failed"));
+        assert_no_key_material(&logs);
+        let signatures = signatures.lock().unwrap();
+        assert!(!signatures.is_empty());
+        for signature in signatures.iter() {
+            assert!(!logs.contains(signature), "signature leaked: {logs}");
+        }
+    }
+
+    #[test]
+    fn debug_redacts_inline_key() {
+        let args = [
+            "whodis",
+            "--zone",
+            "example.com",
+            "--hostname",
+            "h.example.com",
+            "--server",
+            "127.0.0.1",
+            "--key",
+            KEY_PEM,
+        ];
+        let cli = Cli::try_parse_from(args).unwrap();
+        let debug = format!("{cli:?}");
+        assert!(debug.contains("[redacted]"));
+        assert_no_key_material(&debug);
+
+        let key = cli.update.unwrap().key;
+        let material = key_material(None, key.as_ref()).unwrap();
+        assert_eq!(format!("{material:?}"), "[redacted]");
+        assert_eq!(
+            format!("{:?}", key_material(None, None).unwrap()),
+            "[redacted]"
+        );
+    }
+
+    #[test]
+    fn key_errors_do_not_leak_key() {
+        let zone = Name::from_str("example.com.").unwrap();
+        let lines: Vec<&str> = KEY_PEM.lines().collect();
+        // Valid armour and base64 around a truncated DER body, and a body that is not
+        // base64 at all.
+        let truncated = [&lines[..lines.len() / 2], &lines[lines.len() - 1..]].concat();
+        let garbled = KEY_PEM.replacen('M', "!", 1);
+
+        for pem in [truncated.join("\n"), garbled] {
+            let err = ZoneSigners::default()
+                .add(zone.clone(), pem.as_bytes())
+                .unwrap_err();
+            assert_no_key_material(&format!("{err:?}"));
+            assert_no_key_material(&format!("{err:#}"));
+        }
+
+        let err = key_material(Some(Path::new("/nonexistent/whodis.key")), None).unwrap_err();
+        assert_no_key_material(&format!("{err:?}"));
+    }
+
+    #[test]
+    fn dig_redacts_sig0_signature() {
+        let zone = Name::from_str("example.com.").unwrap();
+        let mut signers = ZoneSigners::default();
+        signers.add(zone.clone(), KEY_BYTES).unwrap();
+
+        let mut msg = Message::new();
+        msg.set_op_code(OpCode::Update);
+        let mut zone_section = Query::new();
+        zone_section.set_name(zone.clone());
+        zone_section.set_query_type(RecordType::SOA);
+        msg.add_zone(zone_section);
+        let host = Name::from_str("h.example.com.").unwrap();
+        let addr = rdata::A::new(192, 0, 2, 1);
+        msg.add_update(Record::from_rdata(host, 300, RData::A(addr)));
+
+        let (sigs, _) = signers.finalize_message(&msg, 1_700_000_000).unwrap();
+        assert!(!sigs.is_empty());
+        let mut signatures = Vec::new();
+        for sig in sigs {
+            signatures.push(signature_of(&sig));
+            msg.add_sig0(sig);
+        }
+
+        let dig = Dig(&msg).to_string();
+        assert!(dig.contains("SIG0 PSEUDOSECTION"));
+        assert!(dig.contains("[redacted]"));
+        for signature in signatures {
+            assert!(!dig.contains(&signature), "signature leaked: {dig}");
+        }
+    }
+}
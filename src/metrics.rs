@@ -0,0 +1,82 @@
+use color_eyre::eyre::{Context, Result};
+use std::fmt::Write as _;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Outcome of a single run, as exposed to the node_exporter textfile collector.
+pub struct RunMetrics<'a> {
+    pub zone: &'a str,
+    pub hostname: &'a str,
+    pub success: bool,
+    pub records: usize,
+    pub duration: Duration,
+}
+
+impl RunMetrics<'_> {
+    /// Renders the metrics in Prometheus text exposition format.
+    fn render(&self) -> String {
+        let labels = format!(
+            "zone=\"{}\",hostname=\"{}\"",
+            escape_label(self.zone),
+            escape_label(self.hostname)
+        );
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        let mut out = String::new();
+        let mut gauge = |name: &str, help: &str, value: String| {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} gauge");
+            let _ = writeln!(out, "{name}{{{labels}}} {value}");
+        };
+        gauge(
+            "whodis_last_run_timestamp_seconds",
+            "Unix time at which the last run finished.",
+            timestamp.to_string(),
+        );
+        gauge(
+            "whodis_last_run_success",
+            "Whether the last run published its records (1) or failed (0).",
+            u8::from(self.success).to_string(),
+        );
+        gauge(
+            "whodis_last_run_duration_seconds",
+            "Wall-clock duration of the last run.",
+            self.duration.as_secs_f64().to_string(),
+        );
+        gauge(
+            "whodis_last_run_records",
+            "Number of address records published by the last run.",
+            self.records.to_string(),
+        );
+        out
+    }
+
+    /// Writes the metrics to `path` atomically, so the collector never sees a partial file.
+    pub fn write_textfile(&self, path: &Path) -> Result<()> {
+        let file_name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        // The collector only reads `*.prom`, so the temporary name must not end in it.
+        let tmp = path.with_file_name(format!(".{file_name}.{}.tmp", std::process::id()));
+
+        std::fs::write(&tmp, self.render())
+            .with_context(|| format!("Writing metrics to {}", tmp.display()))?;
+        if let Err(e) = std::fs::rename(&tmp, path) {
+            let _ = std::fs::remove_file(&tmp);
+            return Err(e)
+                .with_context(|| format!("Moving metrics into place at {}", path.display()));
+        }
+        Ok(())
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
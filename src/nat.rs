@@ -0,0 +1,148 @@
+use color_eyre::eyre::{Context, Result, eyre};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::{UdpSocket, lookup_host};
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+const STUN_MAGIC_COOKIE: u32 = 0x2112_A442;
+const NAT_PMP_PORT: u16 = 5351;
+
+/// How the interface address relates to what the rest of the internet sees.
+#[derive(Debug, PartialEq, Eq)]
+pub enum NatVerdict {
+    /// The interface address is the public address.
+    None,
+    /// One NAT: the gateway's external address is the one observed from outside.
+    Single,
+    /// The gateway itself sits behind another NAT.
+    Double { gateway_external: Ipv4Addr },
+    /// The interface or gateway holds an RFC 6598 shared address, i.e. carrier-grade NAT.
+    CarrierGrade { shared: Ipv4Addr },
+    /// Behind NAT, but without a gateway answer we cannot tell how many layers.
+    Unknown,
+}
+
+/// Classifies the addresses gathered from the interface, NAT-PMP and STUN.
+pub fn classify(
+    interface: Ipv4Addr,
+    gateway_external: Option<Ipv4Addr>,
+    observed: Ipv4Addr,
+) -> NatVerdict {
+    if is_shared(interface) {
+        return NatVerdict::CarrierGrade { shared: interface };
+    }
+    if interface == observed {
+        return NatVerdict::None;
+    }
+    match gateway_external {
+        Some(ext) if is_shared(ext) => NatVerdict::CarrierGrade { shared: ext },
+        Some(ext) if ext == observed => NatVerdict::Single,
+        Some(ext) => NatVerdict::Double {
+            gateway_external: ext,
+        },
+        None => NatVerdict::Unknown,
+    }
+}
+
+/// RFC 6598 shared address space (100.64.0.0/10), used between CGNAT and subscribers.
+pub fn is_shared(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    a == 100 && (b & 0xC0) == 64
+}
+
+/// Asks a STUN server (RFC 5389 Binding request) which IPv4 address our packets come from.
+pub async fn stun_observed_address(server: &str) -> Result<Ipv4Addr> {
+    let server = lookup_host(server)
+        .await
+        .with_context(|| format!("Resolving STUN server {server}"))?
+        .find(SocketAddr::is_ipv4)
+        .ok_or_else(|| eyre!("STUN server {server} has no IPv4 address"))?;
+
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    let transaction: [u8; 12] = rand::random();
+
+    let mut request = Vec::with_capacity(20);
+    request.extend_from_slice(&0x0001u16.to_be_bytes()); // Binding request
+    request.extend_from_slice(&0u16.to_be_bytes()); // no attributes
+    request.extend_from_slice(&STUN_MAGIC_COOKIE.to_be_bytes());
+    request.extend_from_slice(&transaction);
+    socket.send_to(&request, server).await?;
+
+    let mut buf = [0u8; 512];
+    let len = tokio::time::timeout(PROBE_TIMEOUT, socket.recv(&mut buf))
+        .await
+        .map_err(|_| eyre!("No STUN answer from {server} within {PROBE_TIMEOUT:?}"))??;
+    parse_stun_response(&buf[..len], &transaction)
+        .ok_or_else(|| eyre!("Unusable STUN response from {server}"))
+}
+
+fn parse_stun_response(msg: &[u8], transaction: &[u8; 12]) -> Option<Ipv4Addr> {
+    if msg.len() < 20 || msg[0..2] != [0x01, 0x01] || &msg[8..20] != transaction {
+        return None;
+    }
+
+    let mut attrs = &msg[20..];
+    let mut mapped = None;
+    while attrs.len() >= 4 {
+        let kind = u16::from_be_bytes([attrs[0], attrs[1]]);
+        let len = usize::from(u16::from_be_bytes([attrs[2], attrs[3]]));
+        let value = attrs.get(4..4 + len)?;
+
+        // Both layouts are: reserved, family, port, address. Family 0x01 is IPv4.
+        if value.len() >= 8 && value[1] == 0x01 {
+            let raw = u32::from_be_bytes([value[4], value[5], value[6], value[7]]);
+            match kind {
+                0x0020 => return Some(Ipv4Addr::from(raw ^ STUN_MAGIC_COOKIE)), // XOR-MAPPED-ADDRESS
+                0x0001 => mapped = Some(Ipv4Addr::from(raw)),                   // MAPPED-ADDRESS
+                _ => {}
+            }
+        }
+        // Attributes are padded to 4-byte boundaries.
+        attrs = attrs.get(4 + len.next_multiple_of(4)..).unwrap_or_default();
+    }
+    mapped
+}
+
+/// Asks the default gateway for its external address over NAT-PMP (RFC 6886).
+/// Returns `None` when there is no gateway or it does not speak NAT-PMP.
+pub async fn gateway_external_address() -> Option<Ipv4Addr> {
+    let gateway = default_gateway()?;
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await.ok()?;
+    socket
+        .send_to(&[0, 0], (gateway, NAT_PMP_PORT))
+        .await
+        .ok()?;
+
+    let mut buf = [0u8; 16];
+    let len = tokio::time::timeout(PROBE_TIMEOUT, socket.recv(&mut buf))
+        .await
+        .ok()?
+        .ok()?;
+    // version 0, opcode 128 (response to 0), result code 0 (success)
+    if len < 12 || buf[0..4] != [0, 128, 0, 0] {
+        return None;
+    }
+    Some(Ipv4Addr::new(buf[8], buf[9], buf[10], buf[11]))
+}
+
+/// Reads the IPv4 default gateway from the kernel routing table.
+#[cfg(target_os = "linux")]
+fn default_gateway() -> Option<IpAddr> {
+    let routes = std::fs::read_to_string("/proc/net/route").ok()?;
+    routes.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            [_, "00000000", gateway, ..] => {
+                let raw = u32::from_str_radix(gateway, 16).ok()?;
+                // The kernel prints the in-memory (network order) address as a native integer.
+                Some(IpAddr::V4(Ipv4Addr::from(raw.to_ne_bytes())))
+            }
+            _ => None,
+        }
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn default_gateway() -> Option<IpAddr> {
+    None
+}
@@ -0,0 +1,143 @@
+use color_eyre::eyre::{Result, eyre};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::time::Instant;
+use tracing::{debug, info};
+
+use crate::IpMode;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Waits until the kernel has a default route for the family `mode` asks for, and the
+/// route's source address is neither loopback nor link-local.
+///
+/// Right after boot or resume the interface may still be unconfigured, or only carry a
+/// link-local address; updating then would fail or publish an unusable address.
+pub async fn wait_until_ready(mode: &IpMode, timeout: Duration) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(ip) = routable_address(mode).await {
+            info!(%ip, "Network is ready");
+            return Ok(());
+        }
+        if Instant::now() + POLL_INTERVAL > deadline {
+            return Err(eyre!(
+                "No routable address or default route within {timeout:?}"
+            ));
+        }
+        debug!("Network not ready yet, waiting");
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+async fn routable_address(mode: &IpMode) -> Option<IpAddr> {
+    // Connecting a UDP socket sends nothing, but makes the kernel pick a route and a
+    // source address for the destination. Documentation addresses are only reachable
+    // through the default route.
+    let probes: &[IpAddr] = match mode {
+        IpMode::V4Only => &[IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))],
+        IpMode::V6Only => &[IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1))],
+        IpMode::Both => &[
+            IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+            IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)),
+        ],
+    };
+
+    for &probe in probes {
+        let bind: IpAddr = match probe {
+            IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            IpAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        let Ok(socket) = UdpSocket::bind((bind, 0)).await else {
+            continue;
+        };
+        if socket.connect((probe, 53)).await.is_err() {
+            continue;
+        }
+        let Ok(local) = socket.local_addr() else {
+            continue;
+        };
+        let ip = local.ip();
+        if !ip.is_loopback() && !is_link_local(ip) {
+            return Some(ip);
+        }
+    }
+    None
+}
+
+fn is_link_local(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => v4.is_link_local(),
+        IpAddr::V6(v6) => v6.is_unicast_link_local(),
+    }
+}
+
+/// An address or CIDR prefix, e.g. `203.0.113.7` or `203.0.113.0/24`.
+#[derive(Clone, Debug)]
+pub struct Prefix {
+    addr: IpAddr,
+    len: u8,
+}
+
+impl Prefix {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(self.len)).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.len))
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Prefix {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (addr, len) = match value.split_once('/') {
+            Some((addr, len)) => (addr, Some(len)),
+            None => (value, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|e| format!("invalid address {addr:?}: {e}"))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let len = match len {
+            Some(len) => len
+                .parse()
+                .ok()
+                .filter(|len| *len <= max)
+                .ok_or_else(|| format!("invalid prefix length {len:?}, expected 0-{max}"))?,
+            None => max,
+        };
+        Ok(Self { addr, len })
+    }
+}
+
+impl fmt::Display for Prefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.len)
+    }
+}
+
+/// Returns the first of `ips` that lies outside all `expected` prefixes of its address
+/// family. A family without any expected prefix is not restricted.
+pub fn unexpected_address(ips: &[IpAddr], expected: &[Prefix]) -> Option<IpAddr> {
+    ips.iter().copied().find(|&ip| {
+        let mut same_family = expected
+            .iter()
+            .filter(|p| p.addr.is_ipv4() == ip.is_ipv4())
+            .peekable();
+        same_family.peek().is_some() && !same_family.any(|p| p.contains(ip))
+    })
+}
@@ -0,0 +1,156 @@
+use color_eyre::eyre::{Context, Result, eyre};
+use hickory_client::client::{Client, ClientHandle};
+use hickory_client::proto::op::ResponseCode;
+use hickory_client::proto::rr::{DNSClass, Name, RData, RecordType};
+use hickory_client::proto::runtime::TokioRuntimeProvider;
+use hickory_client::proto::udp::UdpClientStream;
+use std::fmt::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::nat;
+
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Who announces an address, from Team Cymru's IP-to-ASN DNS service.
+pub struct Origin {
+    pub asn: String,
+    pub as_name: Option<String>,
+    pub prefix: String,
+    pub country: String,
+}
+
+/// A recursive resolver for lookups outside the managed zones.
+pub struct Resolver {
+    client: Client,
+}
+
+impl Resolver {
+    pub async fn connect(addr: SocketAddr) -> Result<Self> {
+        let stream = UdpClientStream::builder(addr, TokioRuntimeProvider::new())
+            .with_timeout(Some(LOOKUP_TIMEOUT))
+            .build();
+        let (client, bg) = Client::connect(stream)
+            .await
+            .with_context(|| format!("Connecting to resolver {addr}"))?;
+        tokio::spawn(bg);
+        Ok(Self { client })
+    }
+
+    /// The first `nameserver` listed in /etc/resolv.conf.
+    pub fn system_default() -> Result<SocketAddr> {
+        let conf =
+            std::fs::read_to_string("/etc/resolv.conf").context("Reading /etc/resolv.conf")?;
+        conf.lines()
+            .find_map(
+                |line| match line.split_whitespace().collect::<Vec<_>>()[..] {
+                    ["nameserver", addr, ..] => addr.parse::<IpAddr>().ok(),
+                    _ => None,
+                },
+            )
+            .map(|ip| SocketAddr::new(ip, 53))
+            .ok_or_else(|| eyre!("No nameserver in /etc/resolv.conf"))
+    }
+
+    pub async fn ptr(&mut self, ip: IpAddr) -> Result<Option<Name>> {
+        let answers = self.lookup(Name::from(ip), RecordType::PTR).await?;
+        Ok(answers.into_iter().find_map(|data| match data {
+            RData::PTR(ptr) => Some(ptr.0),
+            _ => None,
+        }))
+    }
+
+    pub async fn origin(&mut self, ip: IpAddr) -> Result<Option<Origin>> {
+        // e.g. "64500 | 203.0.113.0/24 | US | arin | 2001-01-01"
+        let Some(answer) = self.txt(origin_name(ip)?).await? else {
+            return Ok(None);
+        };
+        let fields: Vec<&str> = answer.split('|').map(str::trim).collect();
+        let [asns, prefix, country, ..] = fields[..] else {
+            return Err(eyre!("Unexpected origin answer {answer:?}"));
+        };
+        // Prefixes announced by several ASes list all of them; the first is enough here.
+        let asn = asns.split_whitespace().next().unwrap_or(asns).to_owned();
+
+        // e.g. "64500 | US | arin | 2001-01-01 | EXAMPLE-ISP, US"
+        let as_name = self
+            .txt(Name::from_str(&format!("AS{asn}.asn.cymru.com."))?)
+            .await?
+            .and_then(|answer| answer.rsplit('|').next().map(|name| name.trim().to_owned()));
+
+        Ok(Some(Origin {
+            asn,
+            as_name,
+            prefix: prefix.to_owned(),
+            country: country.to_owned(),
+        }))
+    }
+
+    async fn txt(&mut self, name: Name) -> Result<Option<String>> {
+        let answers = self.lookup(name, RecordType::TXT).await?;
+        Ok(answers.into_iter().find_map(|data| match data {
+            RData::TXT(txt) => Some(
+                txt.txt_data()
+                    .iter()
+                    .map(|part| String::from_utf8_lossy(part))
+                    .collect(),
+            ),
+            _ => None,
+        }))
+    }
+
+    async fn lookup(&mut self, name: Name, rtype: RecordType) -> Result<Vec<RData>> {
+        let resp = self
+            .client
+            .query(name.clone(), DNSClass::IN, rtype)
+            .await
+            .with_context(|| format!("Looking up {rtype} {name}"))?;
+        match resp.response_code() {
+            ResponseCode::NoError | ResponseCode::NXDomain => {
+                Ok(resp.answers().iter().map(|r| r.data().clone()).collect())
+            }
+            code => Err(eyre!("Resolver answered {code} for {rtype} {name}")),
+        }
+    }
+}
+
+/// The Team Cymru query name: the address reversed like in-addr.arpa / ip6.arpa names.
+fn origin_name(ip: IpAddr) -> Result<Name> {
+    let mut name = String::new();
+    match ip {
+        IpAddr::V4(v4) => {
+            for octet in v4.octets().iter().rev() {
+                write!(name, "{octet}.")?;
+            }
+            name.push_str("origin.asn.cymru.com.");
+        }
+        IpAddr::V6(v6) => {
+            for byte in v6.octets().iter().rev() {
+                write!(name, "{:x}.{:x}.", byte & 0xf, byte >> 4)?;
+            }
+            name.push_str("origin6.asn.cymru.com.");
+        }
+    }
+    Ok(Name::from_str(&name)?)
+}
+
+/// Whether the address is publicly routed, i.e. worth looking up an origin for.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            !(v4.is_private()
+                || v4.is_loopback()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || v4.is_broadcast()
+                || v4.is_documentation()
+                || nat::is_shared(v4))
+        }
+        IpAddr::V6(v6) => {
+            let [first, second, ..] = v6.segments();
+            // Global unicast (2000::/3), minus the documentation prefix.
+            first & 0xe000 == 0x2000 && !(first == 0x2001 && second == 0x0db8)
+        }
+    }
+}
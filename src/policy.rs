@@ -0,0 +1,209 @@
+use color_eyre::eyre::{Context, Result, eyre};
+use hickory_client::proto::rr::{Name, RData, RecordType};
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Client-side limits on what a run may change, checked before an update is signed.
+///
+/// The file is line based and `#` starts a comment. Directives may be repeated, and a
+/// directive that never appears places no restriction:
+///
+/// ```text
+/// zone dyn.lan                # zones that may be updated
+/// name *.dyn.lan nas.lan      # exact names, or `*.` for any name below one
+/// type A AAAA TXT             # record types that may be replaced
+/// ttl 60-3600                 # inclusive TTL bounds in seconds
+/// ```
+#[derive(Debug, Default)]
+pub struct Policy {
+    zones: Vec<Name>,
+    names: Vec<NamePattern>,
+    types: Vec<RecordType>,
+    ttl: Option<RangeInclusive<u32>>,
+}
+
+#[derive(Debug)]
+enum NamePattern {
+    Exact(Name),
+    Below(Name),
+}
+
+impl NamePattern {
+    fn matches(&self, name: &Name) -> bool {
+        match self {
+            Self::Exact(exact) => exact.eq_ignore_root(name),
+            Self::Below(parent) => parent.zone_of(name) && !parent.eq_ignore_root(name),
+        }
+    }
+}
+
+impl Policy {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Reading policy file {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("Parsing policy file {}", path.display()))
+    }
+
+    fn parse(text: &str) -> Result<Self> {
+        let mut policy = Self::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split_once('#').map_or(line, |(code, _)| code);
+            let mut words = line.split_whitespace();
+            let Some(directive) = words.next() else {
+                continue;
+            };
+            let values: Vec<&str> = words.collect();
+            policy
+                .add(directive, &values)
+                .with_context(|| format!("Line {}", number + 1))?;
+        }
+        Ok(policy)
+    }
+
+    fn add(&mut self, directive: &str, values: &[&str]) -> Result<()> {
+        if values.is_empty() {
+            return Err(eyre!("{directive:?} needs at least one value"));
+        }
+
+        match directive {
+            "zone" => {
+                for value in values {
+                    let zone = Name::from_str(value)
+                        .with_context(|| format!("Invalid zone name {value:?}"))?;
+                    self.zones.push(zone);
+                }
+            }
+            "name" => {
+                for value in values {
+                    let pattern = match value.strip_prefix("*.") {
+                        Some(parent) => NamePattern::Below(Name::from_str(parent)?),
+                        None => NamePattern::Exact(Name::from_str(value)?),
+                    };
+                    self.names.push(pattern);
+                }
+            }
+            "type" => {
+                for value in values {
+                    let rtype = RecordType::from_str(&value.to_uppercase())
+                        .with_context(|| format!("Invalid record type {value:?}"))?;
+                    self.types.push(rtype);
+                }
+            }
+            "ttl" => {
+                let [range] = values else {
+                    return Err(eyre!("\"ttl\" takes a single MIN-MAX range"));
+                };
+                let (min, max) = range
+                    .split_once('-')
+                    .ok_or_else(|| eyre!("Expected a TTL range like 60-3600, got {range:?}"))?;
+                let (min, max): (u32, u32) = (min.parse()?, max.parse()?);
+                if min > max {
+                    return Err(eyre!("Empty TTL range {range:?}"));
+                }
+                self.ttl = Some(min..=max);
+            }
+            other => {
+                return Err(eyre!(
+                    "Unknown directive {other:?}; expected zone, name, type or ttl"
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Refuses an update for `zone` that touches a name, record type or TTL outside the
+    /// policy.
+    pub fn check(&self, zone: &Name, updates: &[(Name, u32, Vec<RData>)]) -> Result<()> {
+        if !self.zones.is_empty() && !self.zones.iter().any(|z| z.eq_ignore_root(zone)) {
+            return Err(eyre!("Policy does not allow updating zone {zone}"));
+        }
+
+        for (name, ttl, records) in updates {
+            if !self.names.is_empty() && !self.names.iter().any(|p| p.matches(name)) {
+                return Err(eyre!("Policy does not allow changing {name}"));
+            }
+            if let Some(bounds) = &self.ttl
+                && !bounds.contains(ttl)
+            {
+                return Err(eyre!(
+                    "Policy does not allow TTL {ttl} for {name}; it must be within {}-{}",
+                    bounds.start(),
+                    bounds.end()
+                ));
+            }
+            for rtype in records.iter().map(RData::record_type) {
+                if !self.types.is_empty() && !self.types.contains(&rtype) {
+                    return Err(eyre!("Policy does not allow {rtype} records at {name}"));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_client::proto::rr::rdata;
+
+    fn name(name: &str) -> Name {
+        Name::from_str(name).unwrap()
+    }
+
+    fn a_record(host: &str, ttl: u32) -> (Name, u32, Vec<RData>) {
+        (name(host), ttl, vec![RData::A(rdata::A::new(192, 0, 2, 1))])
+    }
+
+    #[test]
+    fn wildcard_refuses_the_parent_itself() {
+        let policy = Policy::parse("name *.dyn.lan").unwrap();
+        let zone = name("dyn.lan");
+        assert!(policy.check(&zone, &[a_record("nas.dyn.lan", 300)]).is_ok());
+        assert!(policy.check(&zone, &[a_record("a.b.dyn.lan", 300)]).is_ok());
+        assert!(policy.check(&zone, &[a_record("dyn.lan", 300)]).is_err());
+        assert!(policy.check(&zone, &[a_record("dyn.lan.", 300)]).is_err());
+    }
+
+    #[test]
+    fn exact_names_ignore_the_trailing_dot() {
+        let zone = name("dyn.lan");
+        for pattern in ["name nas.dyn.lan", "name nas.dyn.lan."] {
+            let policy = Policy::parse(pattern).unwrap();
+            assert!(policy.check(&zone, &[a_record("nas.dyn.lan", 300)]).is_ok());
+            assert!(
+                policy
+                    .check(&zone, &[a_record("nas.dyn.lan.", 300)])
+                    .is_ok()
+            );
+            assert!(policy.check(&zone, &[a_record("pc.dyn.lan", 300)]).is_err());
+        }
+    }
+
+    #[test]
+    fn ttl_bounds_are_inclusive() {
+        let policy = Policy::parse("ttl 60-3600").unwrap();
+        let zone = name("dyn.lan");
+        for (ttl, allowed) in [(59, false), (60, true), (3600, true), (3601, false)] {
+            let result = policy.check(&zone, &[a_record("nas.dyn.lan", ttl)]);
+            assert_eq!(result.is_ok(), allowed, "TTL {ttl}");
+        }
+    }
+
+    #[test]
+    fn types_are_case_insensitive() {
+        let policy = Policy::parse("type a").unwrap();
+        assert_eq!(policy.types, [RecordType::A]);
+        let zone = name("dyn.lan");
+        assert!(policy.check(&zone, &[a_record("nas.dyn.lan", 300)]).is_ok());
+    }
+
+    #[test]
+    fn invalid_lines_are_reported_with_their_number() {
+        for text in ["zone dyn.lan\nfrobnicate yes", "# limits\nttl 10-5"] {
+            let err = Policy::parse(text).unwrap_err();
+            assert!(format!("{err:#}").starts_with("Line 2: "), "{err:#}");
+        }
+    }
+}
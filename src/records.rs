@@ -0,0 +1,210 @@
+use hickory_client::proto::rr::Name;
+use hickory_client::proto::rr::rdata::naptr::verify_flags;
+use hickory_client::proto::rr::rdata::{NAPTR, TXT};
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Parses a NAPTR value in zone-file order:
+/// `ORDER PREFERENCE "FLAGS" "SERVICE" "REGEXP" REPLACEMENT`,
+/// e.g. `100 10 "S" "SIP+D2U" "" _sip._udp.example.com.`.
+pub fn parse_naptr(value: &str) -> Result<NAPTR, String> {
+    let fields = split_fields(value)?;
+    let [order, preference, flags, service, regexp, replacement] = <[String; 6]>::try_from(fields)
+        .map_err(|fields| {
+            format!(
+                "expected 6 fields (order preference flags service regexp replacement), got {}",
+                fields.len()
+            )
+        })?;
+
+    let order = order
+        .parse()
+        .map_err(|e| format!("invalid order {order:?}: {e}"))?;
+    let preference = preference
+        .parse()
+        .map_err(|e| format!("invalid preference {preference:?}: {e}"))?;
+    if !verify_flags(flags.as_bytes()) {
+        return Err(format!(
+            "invalid flags {flags:?}: only letters and digits are allowed"
+        ));
+    }
+    let replacement = Name::from_str(&replacement)
+        .map_err(|e| format!("invalid replacement {replacement:?}: {e}"))?;
+
+    Ok(NAPTR::new(
+        order,
+        preference,
+        flags.into_bytes().into_boxed_slice(),
+        service.into_bytes().into_boxed_slice(),
+        regexp.into_bytes().into_boxed_slice(),
+        replacement,
+    ))
+}
+
+/// Splits on whitespace, keeping double-quoted fields (which may be empty) together.
+/// Backslash escapes the next character inside quotes, as in zone files.
+fn split_fields(value: &str) -> Result<Vec<String>, String> {
+    let mut fields = Vec::new();
+    let mut chars = value.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let mut field = String::new();
+        if c == '"' {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => field.extend(chars.next()),
+                    Some(c) => field.push(c),
+                    None => return Err(format!("unterminated quote in {value:?}")),
+                }
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                field.push(c);
+                chars.next();
+            }
+        }
+        fields.push(field);
+    }
+
+    Ok(fields)
+}
+
+/// A TXT value whose `{placeholder}`s are filled in at update time. Supported
+/// placeholders are `{ipv4}`, `{ipv6}`, `{hostname}` and `{timestamp}`; `{{` and `}}`
+/// produce literal braces.
+#[derive(Clone, Debug)]
+pub struct TxtTemplate(String);
+
+/// Values substituted into a [`TxtTemplate`].
+pub struct TxtVars<'a> {
+    pub hostname: &'a Name,
+    pub ips: &'a [IpAddr],
+    pub now: SystemTime,
+}
+
+/// Checks the template's placeholders upfront, so typos fail before connecting.
+pub fn parse_txt_template(value: &str) -> Result<TxtTemplate, String> {
+    let template = TxtTemplate(value.to_owned());
+    template.expand(|_| Ok(String::new()))?;
+    Ok(template)
+}
+
+impl TxtTemplate {
+    pub fn render(&self, vars: &TxtVars<'_>) -> Result<String, String> {
+        let join = |v4: bool| {
+            let ips: Vec<String> = vars
+                .ips
+                .iter()
+                .filter(|ip| ip.is_ipv4() == v4)
+                .map(IpAddr::to_string)
+                .collect();
+            if ips.is_empty() {
+                let family = if v4 { "ipv4" } else { "ipv6" };
+                return Err(format!(
+                    "TXT value {:?} uses {{{family}}}, but no {family} address is being published",
+                    self.0
+                ));
+            }
+            Ok(ips.join(","))
+        };
+
+        self.expand(|placeholder| match placeholder {
+            "ipv4" => join(true),
+            "ipv6" => join(false),
+            "hostname" => Ok(vars.hostname.to_string()),
+            "timestamp" => Ok(rfc3339(vars.now)),
+            _ => unreachable!("placeholders are validated on parse"),
+        })
+    }
+
+    fn expand(
+        &self,
+        mut lookup: impl FnMut(&str) -> Result<String, String>,
+    ) -> Result<String, String> {
+        let mut out = String::new();
+        let mut rest = self.0.as_str();
+
+        while let Some(pos) = rest.find(['{', '}']) {
+            out.push_str(&rest[..pos]);
+            let tail = &rest[pos..];
+
+            if let Some(after) = tail.strip_prefix("{{").or_else(|| tail.strip_prefix("}}")) {
+                out.push_str(&tail[..1]);
+                rest = after;
+            } else if let Some(inner) = tail.strip_prefix('{') {
+                let end = inner
+                    .find('}')
+                    .ok_or_else(|| format!("unclosed '{{' in TXT value {:?}", self.0))?;
+                let placeholder = &inner[..end];
+                if !matches!(placeholder, "ipv4" | "ipv6" | "hostname" | "timestamp") {
+                    return Err(format!(
+                        "unknown placeholder {{{placeholder}}} in TXT value {:?}; expected {{ipv4}}, {{ipv6}}, {{hostname}} or {{timestamp}}",
+                        self.0
+                    ));
+                }
+                out.push_str(&lookup(placeholder)?);
+                rest = &inner[end + 1..];
+            } else {
+                return Err(format!("unmatched '}}' in TXT value {:?}", self.0));
+            }
+        }
+        out.push_str(rest);
+        Ok(out)
+    }
+}
+
+/// Formats a time as RFC 3339 in UTC with second precision, e.g. `2024-06-01T12:00:00Z`.
+pub fn rfc3339(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+
+    // Civil-from-days, from Howard Hinnant's date algorithms.
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60
+    )
+}
+
+/// Builds a TXT record from one logical value, splitting it into the 255-byte
+/// character-strings the wire format requires.
+pub fn txt(value: &str) -> TXT {
+    let mut strings = Vec::new();
+    let mut rest = value;
+    while rest.len() > 255 {
+        let mut cut = 255;
+        while !rest.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        let (head, tail) = rest.split_at(cut);
+        strings.push(head.to_owned());
+        rest = tail;
+    }
+    strings.push(rest.to_owned());
+    TXT::new(strings)
+}
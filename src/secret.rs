@@ -0,0 +1,34 @@
+use std::convert::Infallible;
+use std::fmt;
+use std::str::FromStr;
+
+/// Key material that must not end up in logs or error reports.
+///
+/// `Debug` prints a placeholder and there is deliberately no `Display`, so the value
+/// only leaves the wrapper through an explicit [`Secret::expose`].
+#[derive(Clone)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[redacted]")
+    }
+}
+
+impl FromStr for Secret<String> {
+    type Err = Infallible;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Ok(Self(value.to_owned()))
+    }
+}
@@ -0,0 +1,91 @@
+use color_eyre::eyre::{Context, Result, eyre};
+use std::fmt;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use tokio::net::lookup_host;
+
+const DEFAULT_PORT: u16 = 53;
+
+/// A DNS server given as an address or hostname, with an optional port.
+///
+/// Hostnames are resolved when connecting rather than when parsing, so a primary that
+/// moves to a new address is picked up by the next run without editing any unit files.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerAddr {
+    host: String,
+    port: u16,
+}
+
+impl ServerAddr {
+    fn new(ip: IpAddr, port: u16) -> Self {
+        Self {
+            host: ip.to_string(),
+            port,
+        }
+    }
+
+    /// Resolves to every address the server is known by, in resolver order.
+    pub async fn resolve(&self) -> Result<Vec<SocketAddr>> {
+        if let Ok(ip) = self.host.parse::<IpAddr>() {
+            return Ok(vec![SocketAddr::new(ip, self.port)]);
+        }
+
+        let addrs: Vec<SocketAddr> = lookup_host((self.host.as_str(), self.port))
+            .await
+            .with_context(|| format!("Resolving server {self}"))?
+            .collect();
+        if addrs.is_empty() {
+            return Err(eyre!("Server {self} resolved to no addresses"));
+        }
+        Ok(addrs)
+    }
+}
+
+impl FromStr for ServerAddr {
+    type Err = String;
+
+    /// Accepts `ADDR:PORT`, `[V6]:PORT`, a bare `ADDR` or `[V6]`, `HOST:PORT` and a bare
+    /// `HOST`; the port defaults to 53.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if let Ok(addr) = value.parse::<SocketAddr>() {
+            return Ok(Self::new(addr.ip(), addr.port()));
+        }
+        if let Ok(ip) = value.parse::<IpAddr>() {
+            return Ok(Self::new(ip, DEFAULT_PORT));
+        }
+        if let Some(inner) = value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+            let ip = inner
+                .parse::<Ipv6Addr>()
+                .map_err(|e| format!("invalid IPv6 address {inner:?}: {e}"))?;
+            return Ok(Self::new(ip.into(), DEFAULT_PORT));
+        }
+
+        let (host, port) = match value.rsplit_once(':') {
+            Some((host, port)) => {
+                let port = port
+                    .parse()
+                    .map_err(|e| format!("invalid port {port:?} in {value:?}: {e}"))?;
+                (host, port)
+            }
+            None => (value, DEFAULT_PORT),
+        };
+        if host.is_empty() || host.contains([':', '[', ']']) {
+            return Err(format!(
+                "invalid server {value:?}; expected e.g. 192.168.1.53, [2001:db8::53]:53 or ns1.example.com"
+            ));
+        }
+        Ok(Self {
+            host: host.to_owned(),
+            port,
+        })
+    }
+}
+
+impl fmt::Display for ServerAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.host.parse::<IpAddr>() {
+            Ok(ip) => SocketAddr::new(ip, self.port).fmt(f),
+            Err(_) => write!(f, "{}:{}", self.host, self.port),
+        }
+    }
+}
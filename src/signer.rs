@@ -0,0 +1,57 @@
+use color_eyre::eyre::{Context, Result};
+use hickory_client::proto::ProtoError;
+use hickory_client::proto::dnssec::rdata::KEY;
+use hickory_client::proto::dnssec::{SigSigner, SigningKey};
+use hickory_client::proto::op::{Message, MessageFinalizer, MessageVerifier};
+use hickory_client::proto::rr::{Name, Record};
+
+use crate::key_validator;
+
+/// SIG(0) signers for every zone served over one connection.
+///
+/// hickory fixes the finalizer when a connection is created, so a single `SigSigner`
+/// would tie the connection to one zone. This picks the signer matching the zone
+/// section of each UPDATE instead, letting several zones with their own keys share a
+/// connection to the same server.
+#[derive(Default)]
+pub struct ZoneSigners {
+    signers: Vec<(Name, SigSigner)>,
+}
+
+impl ZoneSigners {
+    /// Validates `key_material` and registers it as the signer for `zone`.
+    pub fn add(&mut self, zone: Name, key_material: &[u8]) -> Result<()> {
+        let signing_key = key_validator::load_and_validate(key_material)
+            .with_context(|| format!("Loading key for zone {zone}"))?;
+        let public_key = signing_key.to_public_key().context("Deriving public key")?;
+
+        let signer = SigSigner::sig0(
+            KEY::new_sig0key(&public_key),
+            Box::new(signing_key),
+            zone.clone(),
+        );
+        self.signers.push((zone, signer));
+        Ok(())
+    }
+}
+
+impl MessageFinalizer for ZoneSigners {
+    fn finalize_message(
+        &self,
+        message: &Message,
+        current_time: u32,
+    ) -> Result<(Vec<Record>, Option<MessageVerifier>), ProtoError> {
+        let zone = message
+            .queries()
+            .first()
+            .map(|q| q.name())
+            .ok_or_else(|| ProtoError::from("UPDATE without a zone section"))?;
+
+        let (_, signer) = self
+            .signers
+            .iter()
+            .find(|(name, _)| name == zone)
+            .ok_or_else(|| ProtoError::from(format!("No signing key for zone {zone}")))?;
+        signer.finalize_message(message, current_time)
+    }
+}